@@ -0,0 +1,144 @@
+//! CEL (Common Expression Language) boolean conditions used to filter
+//! systems and subflakes declaratively from config.
+use std::fmt;
+
+use cel_interpreter::{Context, Program};
+use nix_rs::flake::system::System;
+use serde::{de::Error as _, Deserialize, Deserializer};
+use thiserror::Error;
+
+/// A boolean CEL expression, compiled eagerly so that a malformed condition
+/// is reported at config-load time rather than when first evaluated.
+///
+/// Each evaluation is given a fixed context:
+/// - `system`: the full [System] string (e.g. `"x86_64-linux"`)
+/// - `arch`: the CPU architecture (`aarch64`/`x86_64`)
+/// - `os`: the operating system (`linux`/`darwin`)
+/// - `subflake`: the name of the subflake being considered
+#[derive(Clone)]
+pub struct CelCondition {
+    source: String,
+    program: Program,
+}
+
+impl CelCondition {
+    /// Parse and compile a CEL expression, failing fast on syntax errors.
+    pub fn parse(source: &str) -> Result<Self, CelError> {
+        let program = Program::compile(source).map_err(|err| CelError::Parse {
+            expr: source.to_string(),
+            err: err.to_string(),
+        })?;
+        Ok(CelCondition {
+            source: source.to_string(),
+            program,
+        })
+    }
+
+    /// Evaluate this condition for `system` in the context of `subflake`.
+    pub fn eval(&self, system: &System, subflake: &str) -> Result<bool, CelError> {
+        let (os, arch) = split_system(system);
+        let mut ctx = Context::default();
+        ctx.add_variable("system", system.to_string())
+            .and_then(|_| ctx.add_variable("arch", arch))
+            .and_then(|_| ctx.add_variable("os", os))
+            .and_then(|_| ctx.add_variable("subflake", subflake.to_string()))
+            .map_err(|err| self.eval_error(err.to_string()))?;
+        match self.program.execute(&ctx) {
+            Ok(cel_interpreter::Value::Bool(b)) => Ok(b),
+            Ok(other) => Err(self.eval_error(format!("expression did not evaluate to a bool: {:?}", other))),
+            Err(err) => Err(self.eval_error(err.to_string())),
+        }
+    }
+
+    fn eval_error(&self, err: String) -> CelError {
+        CelError::Eval {
+            expr: self.source.clone(),
+            err,
+        }
+    }
+}
+
+impl fmt::Debug for CelCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CelCondition").field(&self.source).finish()
+    }
+}
+
+impl<'de> Deserialize<'de> for CelCondition {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let source = String::deserialize(deserializer)?;
+        CelCondition::parse(&source).map_err(D::Error::custom)
+    }
+}
+
+/// Split a [System] (e.g. `"aarch64-linux"`) into its `(os, arch)` parts.
+fn split_system(system: &System) -> (String, String) {
+    match system.to_string().split_once('-') {
+        Some((arch, os)) => (os.to_string(), arch.to_string()),
+        None => (String::new(), String::new()),
+    }
+}
+
+/// Errors arising from parsing or evaluating a [CelCondition]
+#[derive(Debug, Error)]
+pub enum CelError {
+    #[error("failed to parse CEL expression `{expr}`: {err}")]
+    Parse { expr: String, err: String },
+
+    #[error("failed to evaluate CEL expression `{expr}`: {err}")]
+    Eval { expr: String, err: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_true_for_matching_os_and_arch() {
+        let condition = CelCondition::parse("os == 'linux' && arch == 'x86_64'").unwrap();
+        let system: System = "x86_64-linux".into();
+        assert!(condition.eval(&system, "myflake").unwrap());
+    }
+
+    #[test]
+    fn test_eval_false_for_non_matching_system() {
+        let condition = CelCondition::parse("os == 'linux' && arch == 'x86_64'").unwrap();
+        let system: System = "aarch64-darwin".into();
+        assert!(!condition.eval(&system, "myflake").unwrap());
+    }
+
+    #[test]
+    fn test_eval_uses_subflake_variable() {
+        let condition = CelCondition::parse("subflake == 'frontend'").unwrap();
+        let system: System = "x86_64-linux".into();
+        assert!(condition.eval(&system, "frontend").unwrap());
+        assert!(!condition.eval(&system, "backend").unwrap());
+    }
+
+    #[test]
+    fn test_eval_system_starts_with() {
+        let condition = CelCondition::parse("system.startsWith('aarch64')").unwrap();
+        let aarch64: System = "aarch64-linux".into();
+        let x86_64: System = "x86_64-linux".into();
+        assert!(condition.eval(&aarch64, "x").unwrap());
+        assert!(!condition.eval(&x86_64, "x").unwrap());
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed_expression() {
+        let err = CelCondition::parse("os ==").unwrap_err();
+        assert!(matches!(err, CelError::Parse { .. }));
+    }
+
+    #[test]
+    fn test_split_system() {
+        let system: System = "aarch64-linux".into();
+        assert_eq!(
+            split_system(&system),
+            ("linux".to_string(), "aarch64".to_string())
+        );
+    }
+}
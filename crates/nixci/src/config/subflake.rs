@@ -0,0 +1,37 @@
+use serde::Deserialize;
+
+use crate::cel::CelCondition;
+
+/// Configuration for a subflake to build, as part of a
+/// [`super::subflakes::SubflakesConfig`]
+#[derive(Debug, Deserialize)]
+pub struct SubflakeConfig {
+    /// Directory (relative to the root flake) in which the subflake lives
+    #[serde(default = "default_dir")]
+    pub dir: String,
+
+    /// An optional CEL expression (see [`CelCondition`]) that decides which
+    /// systems (from the project's `SystemsList`) this subflake builds on.
+    ///
+    /// The expression is evaluated once per candidate system with variables
+    /// `system`, `arch`, `os` and `subflake` in scope. When absent, the
+    /// subflake builds on every system in the list. If the condition is
+    /// `false` for every system, the subflake is skipped entirely.
+    #[serde(default)]
+    pub systems_condition: Option<CelCondition>,
+}
+
+fn default_dir() -> String {
+    ".".to_string()
+}
+
+impl Default for SubflakeConfig {
+    /// The default subflake config points to the root flake itself, and
+    /// builds on all systems.
+    fn default() -> Self {
+        SubflakeConfig {
+            dir: default_dir(),
+            systems_condition: None,
+        }
+    }
+}
@@ -2,6 +2,10 @@ use std::collections::BTreeMap;
 
 use serde::Deserialize;
 
+use crate::{errors::ConfigError, nix::system_list::SystemsList};
+
+type Result<T> = std::result::Result<T, ConfigError>;
+
 use super::subflake::SubflakeConfig;
 
 #[derive(Debug, Deserialize)]
@@ -11,6 +15,38 @@ pub struct SubflakesConfig(
     pub BTreeMap<String, SubflakeConfig>,
 );
 
+impl SubflakesConfig {
+    /// Parse a `SubflakesConfig` from JSON, annotating any failure with the
+    /// exact field path that didn't match (e.g. `foo.systems_condition`)
+    /// rather than an opaque `serde_json::Error`.
+    pub fn from_json(path: &str, json: &str) -> Result<Self> {
+        ConfigError::deserialize_json(path, json)
+    }
+
+    /// The systems each subflake builds on, filtered through its
+    /// `systems_condition` (if any) against the project's `all_systems`.
+    ///
+    /// Subflakes whose condition rules out every system are omitted from
+    /// the result entirely (i.e. they don't run at all).
+    pub fn systems_by_subflake(
+        &self,
+        all_systems: &SystemsList,
+    ) -> Result<BTreeMap<&str, SystemsList>> {
+        self.0
+            .iter()
+            .filter_map(|(name, subflake)| {
+                let systems =
+                    match all_systems.filtered(subflake.systems_condition.as_ref(), name) {
+                        Ok(systems) if systems.0.is_empty() => return None,
+                        Ok(systems) => systems,
+                        Err(err) => return Some(Err(err)),
+                    };
+                Some(Ok((name.as_str(), systems)))
+            })
+            .collect()
+    }
+}
+
 impl Default for SubflakesConfig {
     /// Default value contains a single entry for the root flake.
     fn default() -> Self {
@@ -18,4 +54,71 @@ impl Default for SubflakesConfig {
         subflakes.insert("<root>".to_string(), SubflakeConfig::default());
         SubflakesConfig(subflakes)
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cel::CelCondition;
+
+    use super::*;
+
+    #[test]
+    fn test_from_json_reports_path_to_invalid_field() {
+        let json = r#"{"foo": {"dir": 123}}"#;
+        let err = SubflakesConfig::from_json("test-manifest.json", json).unwrap_err();
+        let message = err.to_string();
+        assert!(
+            message.contains("foo.dir"),
+            "expected error to name the offending path `foo.dir`, got: {message}"
+        );
+    }
+
+    #[test]
+    fn test_systems_by_subflake_filters_via_condition() {
+        let mut subflakes = BTreeMap::new();
+        subflakes.insert(
+            "backend".to_string(),
+            SubflakeConfig {
+                systems_condition: Some(CelCondition::parse("os == 'linux'").unwrap()),
+                ..SubflakeConfig::default()
+            },
+        );
+        let config = SubflakesConfig(subflakes);
+        let all_systems = SystemsList(vec!["x86_64-linux".into(), "x86_64-darwin".into()]);
+
+        let by_subflake = config.systems_by_subflake(&all_systems).unwrap();
+
+        assert_eq!(by_subflake["backend"].0, vec!["x86_64-linux".into()]);
+    }
+
+    #[test]
+    fn test_systems_by_subflake_omits_subflake_with_no_matching_systems() {
+        let mut subflakes = BTreeMap::new();
+        subflakes.insert(
+            "backend".to_string(),
+            SubflakeConfig {
+                systems_condition: Some(CelCondition::parse("os == 'plan9'").unwrap()),
+                ..SubflakeConfig::default()
+            },
+        );
+        let config = SubflakesConfig(subflakes);
+        let all_systems = SystemsList(vec!["x86_64-linux".into()]);
+
+        let by_subflake = config.systems_by_subflake(&all_systems).unwrap();
+
+        assert!(!by_subflake.contains_key("backend"));
+    }
+
+    #[test]
+    fn test_systems_by_subflake_keeps_all_systems_when_no_condition() {
+        let config = SubflakesConfig::default();
+        let all_systems = SystemsList(vec!["x86_64-linux".into(), "aarch64-darwin".into()]);
+
+        let by_subflake = config.systems_by_subflake(&all_systems).unwrap();
+
+        assert_eq!(
+            by_subflake["<root>"].0,
+            vec!["x86_64-linux".into(), "aarch64-darwin".into()]
+        );
+    }
 }
\ No newline at end of file
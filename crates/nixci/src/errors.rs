@@ -0,0 +1,43 @@
+//! Structured, non-panicking errors for the systems/subflakes config subsystem
+use nix_rs::command::NixCmdError;
+use thiserror::Error;
+
+/// An error encountered while loading or evaluating systems/subflakes
+/// configuration. Unlike an opaque deserialize failure or a panic, this
+/// carries enough context (the source path, and for deserialize errors the
+/// exact JSON path) to tell the user what to fix.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    /// A value failed to deserialize; `path` identifies *which* config this
+    /// was (e.g. a file path, or a label like `<builtin:NIX_SYSTEMS>`), and
+    /// the source error identifies the exact JSON path within it (e.g.
+    /// `subflakes.foo.systems[2]`) and the offending value.
+    #[error("invalid config at `{path}`: {source}")]
+    Deserialize {
+        path: String,
+        #[source]
+        source: serde_path_to_error::Error<serde_json::Error>,
+    },
+
+    #[error(transparent)]
+    Nix(#[from] NixCmdError),
+
+    #[error(transparent)]
+    Cel(#[from] crate::cel::CelError),
+}
+
+impl ConfigError {
+    /// Deserialize `json` into `T`, annotating any failure with `path` (for
+    /// the caller's context) and the exact JSON path within `json` that
+    /// didn't match.
+    pub fn deserialize_json<T: serde::de::DeserializeOwned>(
+        path: &str,
+        json: &str,
+    ) -> Result<T, ConfigError> {
+        let de = &mut serde_json::Deserializer::from_str(json);
+        serde_path_to_error::deserialize(de).map_err(|source| ConfigError::Deserialize {
+            path: path.to_string(),
+            source,
+        })
+    }
+}
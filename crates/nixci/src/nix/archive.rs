@@ -0,0 +1,277 @@
+//! Offline prefetching of subflake inputs via `nix flake archive`
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+};
+
+use nix_rs::{command::NixCmd, flake::url::FlakeUrl};
+use serde::Deserialize;
+
+use crate::{config::subflakes::SubflakesConfig, nix::flake_ref::FlakeRef};
+
+impl SubflakesConfig {
+    /// Fetch every subflake's inputs into the local store, without
+    /// evaluating or building anything.
+    ///
+    /// Subflakes are visited in the `BTreeMap`'s deterministic order.
+    /// Returns the deduplicated set of store paths fetched, keyed by
+    /// subflake name, so callers can report what was prefetched (or detect
+    /// paths that are still missing after the fact).
+    pub async fn prefetch_all(
+        &self,
+        cmd: &NixCmd,
+        root_flake: &FlakeUrl,
+    ) -> anyhow::Result<BTreeMap<String, Vec<String>>> {
+        let mut fetched = BTreeMap::new();
+        for (name, subflake) in &self.0 {
+            let flake_url = subflake_url(root_flake, &subflake.dir);
+            let mut paths = archive_flake(cmd, &flake_url).await?;
+            // `nix flake archive` doesn't fetch inputs that are `follows`-ed
+            // by another input, since those are never resolved as
+            // independent flakes in the archive tree it returns. Walk the
+            // lock file directly and re-resolve (archive) every locked
+            // input on its own so none of them are missed.
+            for locked_url in locked_input_urls(cmd, &flake_url).await? {
+                paths.extend(archive_flake(cmd, &locked_url).await?);
+            }
+            fetched.insert(name.clone(), paths.into_iter().collect());
+        }
+        Ok(fetched)
+    }
+}
+
+/// Construct the flake URL for a subflake living in `dir` relative to the root flake.
+///
+/// `root_flake` may already carry its own query string (e.g. a `git+...
+/// ?ref=main` or `github:owner/repo?dir=x` reference), so `dir` can't just
+/// be string-concatenated on with a leading `?` -- that would produce a
+/// malformed reference with two `?`s. Parse it into a [FlakeRef] and set
+/// `dir` through the structured field where one exists (`github:`); for
+/// other schemes, merge it onto whatever query string is already present.
+pub(crate) fn subflake_url(root_flake: &FlakeUrl, dir: &str) -> FlakeUrl {
+    if dir == "." {
+        return root_flake.clone();
+    }
+    match FlakeRef::from_str(&root_flake.0) {
+        Ok(FlakeRef::Github {
+            owner,
+            repo,
+            r#ref,
+            ..
+        }) => FlakeRef::Github {
+            owner,
+            repo,
+            r#ref,
+            dir: Some(dir.to_string()),
+        }
+        .to_flake_url(),
+        _ => {
+            let separator = if root_flake.0.contains('?') { '&' } else { '?' };
+            FlakeUrl(format!("{}{separator}dir={dir}", root_flake.0))
+        }
+    }
+}
+
+/// Run `nix flake archive --json` on `flake_url` and collect the resolved
+/// store paths of the flake itself and all of the inputs it reports
+/// (everything reachable except `follows`-ed inputs; see [locked_input_urls]
+/// for those).
+async fn archive_flake(cmd: &NixCmd, flake_url: &FlakeUrl) -> anyhow::Result<BTreeSet<String>> {
+    let output = cmd
+        .run_with_args_expecting_json::<FlakeArchiveOutput>(&[
+            "flake",
+            "archive",
+            "--json",
+            &flake_url.0,
+        ])
+        .await?;
+    let mut paths = BTreeSet::new();
+    output.collect_store_paths(&mut paths);
+    Ok(paths)
+}
+
+/// Walk `flake_url`'s lock file (via `nix flake metadata --json`) and return
+/// the flake URL of every *locked* input node, so each can be archived on
+/// its own. This is how we pick up inputs that `nix flake archive` left out
+/// because they're `follows`-ed by another input -- the lock file still
+/// records what they're pinned to, independent of who points at them.
+async fn locked_input_urls(cmd: &NixCmd, flake_url: &FlakeUrl) -> anyhow::Result<Vec<FlakeUrl>> {
+    let metadata = cmd
+        .run_with_args_expecting_json::<FlakeMetadata>(&[
+            "flake",
+            "metadata",
+            "--json",
+            &flake_url.0,
+        ])
+        .await?;
+    Ok(metadata
+        .locks
+        .nodes
+        .values()
+        .filter_map(|node| node.locked.as_ref())
+        .filter_map(LockedRef::to_flake_url)
+        .collect())
+}
+
+/// JSON shape of `nix flake archive --json`: the archived flake's own store
+/// path, plus one entry per input that `archive` chose to fetch directly,
+/// recursively.
+#[derive(Debug, Default, Deserialize)]
+struct FlakeArchiveOutput {
+    path: String,
+    #[serde(default)]
+    inputs: BTreeMap<String, FlakeArchiveOutput>,
+}
+
+impl FlakeArchiveOutput {
+    fn collect_store_paths(&self, paths: &mut BTreeSet<String>) {
+        paths.insert(self.path.clone());
+        for input in self.inputs.values() {
+            input.collect_store_paths(paths);
+        }
+    }
+}
+
+/// The subset of `nix flake metadata --json`'s lock file we need: every
+/// node's `locked` reference.
+#[derive(Debug, Deserialize)]
+struct FlakeMetadata {
+    locks: Locks,
+}
+
+#[derive(Debug, Deserialize)]
+struct Locks {
+    nodes: BTreeMap<String, LockNode>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct LockNode {
+    #[serde(default)]
+    locked: Option<LockedRef>,
+}
+
+/// A lock node's resolved (pinned) source, in the subset of shapes we know
+/// how to turn back into a fetchable flake URL. Anything else (e.g. `path`
+/// inputs, which aren't independently fetchable) is ignored.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum LockedRef {
+    Github {
+        owner: String,
+        repo: String,
+        rev: String,
+    },
+    Git {
+        url: String,
+        #[serde(default)]
+        rev: Option<String>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+impl LockedRef {
+    fn to_flake_url(&self) -> Option<FlakeUrl> {
+        match self {
+            LockedRef::Github { owner, repo, rev } => {
+                Some(FlakeUrl(format!("github:{owner}/{repo}/{rev}")))
+            }
+            LockedRef::Git { url, rev } => Some(FlakeUrl(match rev {
+                Some(rev) => format!("git+{url}?rev={rev}"),
+                None => format!("git+{url}"),
+            })),
+            LockedRef::Other => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subflake_url_returns_root_flake_unchanged_for_dot() {
+        let root = FlakeUrl("git+https://example.com/repo.git?ref=main".to_string());
+        assert_eq!(subflake_url(&root, "."), root);
+    }
+
+    #[test]
+    fn test_subflake_url_sets_dir_via_structured_field_for_github() {
+        let root = FlakeUrl("github:owner/repo".to_string());
+        assert_eq!(subflake_url(&root, "sub").0, "github:owner/repo?dir=sub");
+    }
+
+    #[test]
+    fn test_subflake_url_merges_dir_with_existing_query_params() {
+        let root = FlakeUrl("git+https://example.com/repo.git?ref=main".to_string());
+        assert_eq!(
+            subflake_url(&root, "sub").0,
+            "git+https://example.com/repo.git?ref=main&dir=sub"
+        );
+    }
+
+    #[test]
+    fn test_locked_ref_to_flake_url_github() {
+        let locked = LockedRef::Github {
+            owner: "nix-systems".to_string(),
+            repo: "default-linux".to_string(),
+            rev: "deadbeef".to_string(),
+        };
+        assert_eq!(
+            locked.to_flake_url(),
+            Some(FlakeUrl(
+                "github:nix-systems/default-linux/deadbeef".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_locked_ref_to_flake_url_git() {
+        let locked = LockedRef::Git {
+            url: "https://example.com/repo.git".to_string(),
+            rev: Some("deadbeef".to_string()),
+        };
+        assert_eq!(
+            locked.to_flake_url(),
+            Some(FlakeUrl(
+                "git+https://example.com/repo.git?rev=deadbeef".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_locked_ref_other_has_no_flake_url() {
+        assert_eq!(LockedRef::Other.to_flake_url(), None);
+    }
+
+    #[test]
+    fn test_parse_flake_metadata_locks() {
+        let json = r#"{
+            "locks": {
+                "nodes": {
+                    "root": { "inputs": { "nixpkgs": "nixpkgs" } },
+                    "nixpkgs": {
+                        "locked": {
+                            "type": "github",
+                            "owner": "NixOS",
+                            "repo": "nixpkgs",
+                            "rev": "deadbeef"
+                        }
+                    }
+                }
+            }
+        }"#;
+        let metadata: FlakeMetadata = serde_json::from_str(json).unwrap();
+        let urls: Vec<FlakeUrl> = metadata
+            .locks
+            .nodes
+            .values()
+            .filter_map(|node| node.locked.as_ref())
+            .filter_map(LockedRef::to_flake_url)
+            .collect();
+        assert_eq!(
+            urls,
+            vec![FlakeUrl("github:NixOS/nixpkgs/deadbeef".to_string())]
+        );
+    }
+}
@@ -0,0 +1,215 @@
+//! Binary-cache availability preflight
+//!
+//! Before building, check whether a system/subflake's output derivation is
+//! already present in a binary cache (e.g. `https://cache.nixos.org`), so CI
+//! can skip systems that are fully cached rather than always rebuilding
+//! everything.
+use std::collections::BTreeMap;
+
+use futures::{stream, StreamExt};
+use nix_rs::{
+    command::NixCmd,
+    flake::{system::System, url::FlakeUrl},
+};
+use reqwest::StatusCode;
+
+use crate::{
+    config::subflakes::SubflakesConfig,
+    nix::{archive::subflake_url, system_list::SystemsList},
+};
+
+/// How many `narinfo` requests to have in flight at once. Bounded so that a
+/// systems list with hundreds of outputs doesn't open hundreds of
+/// simultaneous sockets against the cache.
+const DEFAULT_CONCURRENCY: usize = 16;
+
+/// One `(system, subflake)` combination's output store path, awaiting a
+/// cache lookup.
+#[derive(Debug, Clone)]
+pub struct OutputToCheck {
+    pub system: System,
+    pub subflake: String,
+    /// The output path's store hash (the part before the first `-`), as
+    /// evaluated directly from the output derivation's `.outPath`.
+    pub store_path_hash: String,
+}
+
+/// Per-system count of how many outputs are already cached vs. missing.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheReport {
+    pub cached: usize,
+    pub missing: usize,
+}
+
+/// For each `(system, subflake)` combination in `systems_by_subflake`,
+/// evaluate its output derivation's `.outPath` so it's ready for a
+/// [preflight] cache lookup.
+pub async fn outputs_to_check(
+    cmd: &NixCmd,
+    root_flake: &FlakeUrl,
+    subflakes: &SubflakesConfig,
+    systems_by_subflake: &BTreeMap<String, SystemsList>,
+) -> anyhow::Result<Vec<OutputToCheck>> {
+    let mut outputs = Vec::new();
+    for (name, systems) in systems_by_subflake {
+        let subflake = subflakes
+            .0
+            .get(name.as_str())
+            .ok_or_else(|| anyhow::anyhow!("no such subflake `{name}`"))?;
+        let flake_url = subflake_url(root_flake, &subflake.dir);
+        for system in &systems.0 {
+            let store_path_hash = resolve_output_hash(cmd, &flake_url, system).await?;
+            outputs.push(OutputToCheck {
+                system: system.clone(),
+                subflake: name.clone(),
+                store_path_hash,
+            });
+        }
+    }
+    Ok(outputs)
+}
+
+/// Evaluate `flake_url`'s default package for `system` and resolve its
+/// output store path down to the hash (the part `<cache>/<hash>.narinfo`
+/// keys on).
+///
+/// This evaluates `.outPath` directly rather than going through `.drvPath`
+/// and `nix path-info`: the latter reports on the `.drv` file itself, not
+/// the build output a binary cache actually serves `narinfo` for.
+async fn resolve_output_hash(
+    cmd: &NixCmd,
+    flake_url: &FlakeUrl,
+    system: &System,
+) -> anyhow::Result<String> {
+    let attr = format!("packages.{system}.default");
+    let expr = out_path_expr(flake_url, &attr);
+    let out_path = cmd
+        .run_with_args_expecting_json::<String>(&["eval", "--json", &expr])
+        .await?;
+    store_path_hash(&out_path)
+}
+
+/// The `nix eval` installable string for `attr`'s output store path.
+fn out_path_expr(flake_url: &FlakeUrl, attr: &str) -> String {
+    format!("{}#{}.outPath", flake_url.0, attr)
+}
+
+/// The store path hash is the part of the base name before the first `-`
+/// (e.g. `/nix/store/<hash>-foo-1.0` -> `<hash>`).
+fn store_path_hash(store_path: &str) -> anyhow::Result<String> {
+    let base = store_path.rsplit('/').next().unwrap_or(store_path);
+    base.split_once('-')
+        .map(|(hash, _)| hash.to_string())
+        .ok_or_else(|| anyhow::anyhow!("not a valid store path: {store_path}"))
+}
+
+/// Query `cache_url` for each `output`'s `<hash>.narinfo`, with bounded
+/// concurrency, and tally the results per system.
+pub async fn preflight(
+    cache_url: &str,
+    outputs: Vec<OutputToCheck>,
+) -> anyhow::Result<BTreeMap<System, CacheReport>> {
+    let client = reqwest::Client::new();
+    let results: Vec<(System, bool)> = stream::iter(outputs)
+        .map(|output| {
+            let client = client.clone();
+            let cache_url = cache_url.to_string();
+            async move {
+                let cached = narinfo_exists(&client, &cache_url, &output.store_path_hash).await?;
+                anyhow::Ok((output.system, cached))
+            }
+        })
+        .buffer_unordered(DEFAULT_CONCURRENCY)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(tally(results))
+}
+
+/// Tally per-system cached/missing counts from a flat list of results.
+fn tally(results: Vec<(System, bool)>) -> BTreeMap<System, CacheReport> {
+    let mut report: BTreeMap<System, CacheReport> = BTreeMap::new();
+    for (system, cached) in results {
+        let entry = report.entry(system).or_default();
+        if cached {
+            entry.cached += 1;
+        } else {
+            entry.missing += 1;
+        }
+    }
+    report
+}
+
+/// Check whether `<cache_url>/<hash>.narinfo` exists, via a `HEAD` request.
+async fn narinfo_exists(
+    client: &reqwest::Client,
+    cache_url: &str,
+    store_path_hash: &str,
+) -> anyhow::Result<bool> {
+    let url = format!(
+        "{}/{}.narinfo",
+        cache_url.trim_end_matches('/'),
+        store_path_hash
+    );
+    let resp = client.head(&url).send().await?;
+    Ok(resp.status() == StatusCode::OK)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_out_path_expr_targets_out_path_not_drv_path() {
+        let flake_url = FlakeUrl("github:foo/bar".to_string());
+        let expr = out_path_expr(&flake_url, "packages.x86_64-linux.default");
+        assert_eq!(
+            expr,
+            "github:foo/bar#packages.x86_64-linux.default.outPath"
+        );
+        assert!(expr.ends_with(".outPath"));
+        assert!(!expr.contains("drvPath"));
+    }
+
+    #[test]
+    fn test_store_path_hash() {
+        assert_eq!(
+            store_path_hash("/nix/store/abc123-foo-1.0").unwrap(),
+            "abc123"
+        );
+    }
+
+    #[test]
+    fn test_store_path_hash_rejects_path_without_dash() {
+        assert!(store_path_hash("nohashseparator").is_err());
+    }
+
+    #[test]
+    fn test_tally_counts_cached_and_missing_per_system() {
+        let linux: System = "x86_64-linux".into();
+        let darwin: System = "x86_64-darwin".into();
+        let results = vec![
+            (linux.clone(), true),
+            (linux.clone(), false),
+            (linux.clone(), true),
+            (darwin.clone(), false),
+        ];
+        let report = tally(results);
+        assert_eq!(
+            report[&linux],
+            CacheReport {
+                cached: 2,
+                missing: 1
+            }
+        );
+        assert_eq!(
+            report[&darwin],
+            CacheReport {
+                cached: 0,
+                missing: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,294 @@
+//! Structured parsing of Nix flake references.
+//!
+//! `nix` accepts flake references as `<scheme>:<path>[?params]` strings. We
+//! used to pass these around as an untyped [FlakeUrl], which meant any
+//! scheme-specific validation (e.g. "this systems list must be a local
+//! path") had to re-parse the string ad hoc wherever it was needed. [FlakeRef]
+//! parses the reference once, into one variant per scheme, so callers can
+//! match on *what kind* of reference they have.
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use nix_rs::flake::url::FlakeUrl;
+use thiserror::Error;
+
+/// A parsed Nix flake reference.
+///
+/// Variants cover the schemes `nixci` cares about; anything else round-trips
+/// through `nix` fine but we don't need to reason about its shape, so parsing
+/// such a reference returns [FlakeRefParseError::UnrecognizedScheme].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlakeRef {
+    /// `github:owner/repo[/ref]?[dir=...]`
+    Github {
+        owner: String,
+        repo: String,
+        r#ref: Option<String>,
+        dir: Option<String>,
+    },
+    /// `git+<url>[?ref=...][&rev=...]`. `protocol` is set for `http`,
+    /// `https` and `ssh`; for any other form (e.g. `git+file://`) it is
+    /// `None` and `url` keeps its own scheme. A bare `git://` is not parsed.
+    Git {
+        url: String,
+        protocol: Option<String>,
+        r#ref: Option<String>,
+        rev: Option<String>,
+    },
+    /// `path:<path>`, or a bare filesystem path (absolute or `.`-relative)
+    Path {
+        path: String,
+        /// Whether the input used an explicit `path:` prefix, vs. a bare
+        /// path. Tracked (rather than normalized away) so `Display`
+        /// reproduces the same input form.
+        explicit_prefix: bool,
+    },
+    /// `tarball+<url>`
+    Tarball { url: String },
+    /// `file:<url>` or `file+<url>`
+    File {
+        url: String,
+        /// Whether the input used the `file+` prefix, vs. `file:`. Tracked
+        /// so `Display` reproduces the same input form.
+        plus_form: bool,
+    },
+}
+
+impl FlakeRef {
+    /// Convert back to the untyped [FlakeUrl] that `nix` CLI invocations expect.
+    pub fn to_flake_url(&self) -> FlakeUrl {
+        FlakeUrl(self.to_string())
+    }
+}
+
+impl FromStr for FlakeRef {
+    type Err = FlakeRefParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = s.strip_prefix("github:") {
+            return parse_github(rest);
+        }
+        if let Some(rest) = s.strip_prefix("tarball+") {
+            return Ok(FlakeRef::Tarball {
+                url: rest.to_string(),
+            });
+        }
+        if let Some(rest) = s.strip_prefix("file+") {
+            return Ok(FlakeRef::File {
+                url: rest.to_string(),
+                plus_form: true,
+            });
+        }
+        if let Some(rest) = s.strip_prefix("file:") {
+            return Ok(FlakeRef::File {
+                url: rest.to_string(),
+                plus_form: false,
+            });
+        }
+        if let Some(rest) = s.strip_prefix("path:") {
+            return Ok(FlakeRef::Path {
+                path: rest.to_string(),
+                explicit_prefix: true,
+            });
+        }
+        for protocol in ["http", "https", "ssh"] {
+            if let Some(rest) = s.strip_prefix(&format!("git+{protocol}://")) {
+                return parse_git(rest, Some(protocol.to_string()));
+            }
+        }
+        if let Some(rest) = s.strip_prefix("git+") {
+            return parse_git(rest, None);
+        }
+        if s.starts_with('/') || s == "." || s.starts_with("./") {
+            return Ok(FlakeRef::Path {
+                path: s.to_string(),
+                explicit_prefix: false,
+            });
+        }
+        Err(FlakeRefParseError::UnrecognizedScheme(s.to_string()))
+    }
+}
+
+impl fmt::Display for FlakeRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlakeRef::Github {
+                owner,
+                repo,
+                r#ref,
+                dir,
+            } => {
+                write!(f, "github:{owner}/{repo}")?;
+                if let Some(r#ref) = r#ref {
+                    write!(f, "/{ref}")?;
+                }
+                write_query(f, &[("dir", dir.as_deref())])
+            }
+            FlakeRef::Git {
+                url,
+                protocol,
+                r#ref,
+                rev,
+            } => {
+                match protocol {
+                    Some(protocol) => write!(f, "git+{protocol}://{url}")?,
+                    None => write!(f, "git+{url}")?,
+                }
+                write_query(f, &[("ref", r#ref.as_deref()), ("rev", rev.as_deref())])
+            }
+            FlakeRef::Path {
+                path,
+                explicit_prefix,
+            } => {
+                if *explicit_prefix {
+                    write!(f, "path:{path}")
+                } else {
+                    write!(f, "{path}")
+                }
+            }
+            FlakeRef::Tarball { url } => write!(f, "tarball+{url}"),
+            FlakeRef::File { url, plus_form } => {
+                if *plus_form {
+                    write!(f, "file+{url}")
+                } else {
+                    write!(f, "file:{url}")
+                }
+            }
+        }
+    }
+}
+
+fn write_query(f: &mut fmt::Formatter<'_>, params: &[(&str, Option<&str>)]) -> fmt::Result {
+    let present: Vec<(&str, &str)> = params
+        .iter()
+        .filter_map(|(k, v)| v.map(|v| (*k, v)))
+        .collect();
+    if present.is_empty() {
+        return Ok(());
+    }
+    write!(f, "?")?;
+    for (i, (k, v)) in present.iter().enumerate() {
+        if i > 0 {
+            write!(f, "&")?;
+        }
+        write!(f, "{k}={v}")?;
+    }
+    Ok(())
+}
+
+fn parse_query(s: &str) -> (String, BTreeMap<String, String>) {
+    match s.split_once('?') {
+        Some((base, query)) => {
+            let params = query
+                .split('&')
+                .filter_map(|kv| kv.split_once('='))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            (base.to_string(), params)
+        }
+        None => (s.to_string(), BTreeMap::new()),
+    }
+}
+
+fn parse_github(rest: &str) -> Result<FlakeRef, FlakeRefParseError> {
+    let (base, mut params) = parse_query(rest);
+    let mut parts = base.splitn(3, '/');
+    let owner = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| FlakeRefParseError::MissingField("owner", rest.to_string()))?
+        .to_string();
+    let repo = parts
+        .next()
+        .filter(|s| !s.is_empty())
+        .ok_or_else(|| FlakeRefParseError::MissingField("repo", rest.to_string()))?
+        .to_string();
+    let r#ref = parts.next().map(|s| s.to_string());
+    let dir = params.remove("dir");
+    Ok(FlakeRef::Github {
+        owner,
+        repo,
+        r#ref,
+        dir,
+    })
+}
+
+fn parse_git(rest: &str, protocol: Option<String>) -> Result<FlakeRef, FlakeRefParseError> {
+    let (url, mut params) = parse_query(rest);
+    if url.is_empty() {
+        return Err(FlakeRefParseError::MissingField("url", rest.to_string()));
+    }
+    let r#ref = params.remove("ref");
+    let rev = params.remove("rev");
+    Ok(FlakeRef::Git {
+        url,
+        protocol,
+        r#ref,
+        rev,
+    })
+}
+
+/// Errors that can occur when parsing a [FlakeRef] from a string.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum FlakeRefParseError {
+    #[error("unrecognized flake reference scheme: {0}")]
+    UnrecognizedScheme(String),
+
+    #[error("flake reference is missing its `{0}` field: {1}")]
+    MissingField(&'static str, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_roundtrip(s: &str) {
+        let parsed: FlakeRef = s.parse().unwrap();
+        assert_eq!(parsed.to_string(), s, "roundtrip failed for {s}");
+    }
+
+    #[test]
+    fn test_roundtrip_github() {
+        assert_roundtrip("github:nix-systems/default-linux");
+        assert_roundtrip("github:nix-systems/default-linux/main");
+        assert_roundtrip("github:owner/repo?dir=subdir");
+    }
+
+    #[test]
+    fn test_roundtrip_git() {
+        assert_roundtrip("git+https://example.com/repo.git?ref=main&rev=deadbeef");
+        assert_roundtrip("git+ssh://example.com/repo.git");
+        assert_roundtrip("git+file:///tmp/repo");
+    }
+
+    #[test]
+    fn test_roundtrip_path() {
+        assert_roundtrip("/home/user/flake");
+        assert_roundtrip(".");
+        assert_roundtrip("path:/home/user/flake");
+    }
+
+    #[test]
+    fn test_roundtrip_tarball() {
+        assert_roundtrip("tarball+https://example.com/archive.tar.gz");
+    }
+
+    #[test]
+    fn test_roundtrip_file() {
+        assert_roundtrip("file:///tmp/foo.json");
+        assert_roundtrip("file+https://example.com/foo.json");
+    }
+
+    #[test]
+    fn test_parse_github_fields() {
+        let r: FlakeRef = "github:nix-systems/aarch64-linux".parse().unwrap();
+        assert_eq!(
+            r,
+            FlakeRef::Github {
+                owner: "nix-systems".to_string(),
+                repo: "aarch64-linux".to_string(),
+                r#ref: None,
+                dir: None,
+            }
+        );
+    }
+}
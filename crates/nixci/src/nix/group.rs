@@ -0,0 +1,146 @@
+//! Bulk loading of systems lists from a manifest file
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Context, Result};
+use futures::future::try_join_all;
+use nix_rs::{command::NixCmd, flake::system::System};
+use serde::Deserialize;
+
+use super::system_list::{SystemsList, SystemsListFlakeRef};
+
+/// A manifest enumerating multiple systems lists to load together.
+///
+/// Useful when a project tracks several upstream `nix-systems/*`-style
+/// lists, or vendors platform sets across repos, and wants a single entry
+/// point rather than hand-merging each one. Entries are keyed by a
+/// diagnostic label (e.g. the manifest's own key for that entry), so load
+/// failures point back to the offending entry rather than a flat merged
+/// blob.
+#[derive(Debug, Deserialize)]
+pub struct GroupManifest(pub BTreeMap<String, GroupEntry>);
+
+/// One entry in a [GroupManifest].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum GroupEntry {
+    /// A systems list flake reference given directly in the manifest.
+    Inline(SystemsListFlakeRef),
+    /// A path to a file whose contents are a single systems list flake
+    /// reference string.
+    File { file: String },
+}
+
+/// The result of loading a [GroupManifest].
+#[derive(Debug)]
+pub struct GroupLoadResult {
+    /// The deduplicated, merged systems across all manifest entries.
+    pub systems: SystemsList,
+    /// Which manifest entry (by its label) produced which systems, so
+    /// diagnostics can point back to the offending line.
+    pub by_entry: BTreeMap<String, SystemsList>,
+}
+
+impl GroupManifest {
+    /// Parse a manifest from its file contents, as TOML or JSON depending on
+    /// `path`'s extension (JSON is assumed for anything else).
+    pub fn parse(path: &Path, contents: &str) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::from_str(contents)
+                .with_context(|| format!("parsing manifest {}", path.display())),
+            _ => serde_json::from_str(contents)
+                .with_context(|| format!("parsing manifest {}", path.display())),
+        }
+    }
+
+    /// Load every entry in this manifest, evaluating flakes concurrently,
+    /// and merge the results into one deduplicated [SystemsList].
+    pub async fn load(&self, cmd: &NixCmd) -> Result<GroupLoadResult> {
+        let entries = try_join_all(self.0.iter().map(|(label, entry)| async move {
+            let flake_ref = entry.resolve(label)?;
+            let systems = SystemsList::from_flake(cmd, &flake_ref)
+                .await
+                .with_context(|| format!("loading manifest entry `{label}`"))?;
+            Result::<_, anyhow::Error>::Ok((label.clone(), systems))
+        }))
+        .await?;
+
+        let mut seen = BTreeSet::new();
+        let mut merged = Vec::new();
+        let mut by_entry = BTreeMap::new();
+        for (label, systems) in entries {
+            for system in &systems.0 {
+                if seen.insert(system.clone()) {
+                    merged.push(system.clone());
+                }
+            }
+            by_entry.insert(label, systems);
+        }
+        Ok(GroupLoadResult {
+            systems: SystemsList(merged),
+            by_entry,
+        })
+    }
+}
+
+impl GroupEntry {
+    fn resolve(&self, label: &str) -> Result<SystemsListFlakeRef> {
+        match self {
+            GroupEntry::Inline(flake_ref) => Ok(flake_ref.clone()),
+            GroupEntry::File { file } => {
+                let contents = std::fs::read_to_string(file).with_context(|| {
+                    format!("manifest entry `{label}` references file `{file}`")
+                })?;
+                SystemsListFlakeRef::from_str(contents.trim())
+                    .map_err(|err| anyhow::anyhow!("manifest entry `{label}` ({file}): {err}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn system(s: &str) -> System {
+        s.into()
+    }
+
+    /// Entry `a` lists two systems, which `SystemsList::from_known_flake`
+    /// (one system per known flake) cannot produce, so it is evaluated
+    /// through `nix`; this test needs `nix` and network access.
+    #[tokio::test]
+    async fn test_load_merges_and_dedups_across_entries() {
+        let mut entries = BTreeMap::new();
+        entries.insert(
+            "a".to_string(),
+            GroupEntry::Inline(
+                SystemsListFlakeRef::from_str("github:nix-systems/default-linux").unwrap(),
+            ),
+        );
+        entries.insert(
+            "b".to_string(),
+            GroupEntry::Inline(
+                SystemsListFlakeRef::from_str("github:nix-systems/aarch64-linux").unwrap(),
+            ),
+        );
+        let manifest = GroupManifest(entries);
+
+        let result = manifest.load(&NixCmd::default()).await.unwrap();
+
+        // Deduplicated union, in manifest (BTreeMap/asciibetical) order.
+        assert_eq!(
+            result.systems.0,
+            vec![system("aarch64-linux"), system("x86_64-linux")]
+        );
+        // Each entry's own systems are still reachable for diagnostics.
+        assert_eq!(
+            result.by_entry["a"].0,
+            vec![system("aarch64-linux"), system("x86_64-linux")]
+        );
+        assert_eq!(result.by_entry["b"].0, vec![system("aarch64-linux")]);
+    }
+}
@@ -1,34 +1,60 @@
 //! Dealing with system lists
 use std::{collections::HashMap, str::FromStr};
 
-use anyhow::Result;
 use lazy_static::lazy_static;
 use nix_rs::{
-    command::{NixCmd, NixCmdError},
+    command::NixCmd,
     flake::{system::System, url::FlakeUrl},
 };
+use serde::{de::Error as _, Deserialize, Deserializer};
 
-/// A flake URL that references a list of systems ([SystemsList])
+use crate::{cel::CelCondition, errors::ConfigError, nix::flake_ref::FlakeRef};
+
+/// Alias used throughout this module: every fallible operation here
+/// ultimately bottoms out in a [ConfigError] (a `nix` command failure, a
+/// CEL evaluation failure, or a deserialize failure), rather than an opaque
+/// `anyhow::Error`.
+type Result<T> = std::result::Result<T, ConfigError>;
+
+/// A flake reference that references a list of systems ([SystemsList])
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct SystemsListFlakeRef(pub FlakeUrl);
+pub struct SystemsListFlakeRef(pub FlakeRef);
 
 lazy_static! {
-    /// As a HashMap<String, String>
-    pub static ref NIX_SYSTEMS: HashMap<String, FlakeUrl> = {
-        serde_json::from_str(env!("NIX_SYSTEMS")).unwrap()
-    };
+    static ref NIX_SYSTEMS_RESULT: Result<HashMap<String, FlakeUrl>> =
+        ConfigError::deserialize_json("<builtin:NIX_SYSTEMS>", env!("NIX_SYSTEMS"));
+}
+
+/// The built-in lookup table of `github:nix-systems/*` flakes, by URL.
+///
+/// Returns `Err` if the table embedded at build time failed to parse --
+/// which would be a bug in this crate's build, not user error -- rather
+/// than panicking at startup.
+pub fn nix_systems() -> std::result::Result<&'static HashMap<String, FlakeUrl>, &'static ConfigError>
+{
+    NIX_SYSTEMS_RESULT.as_ref()
 }
 
 impl FromStr for SystemsListFlakeRef {
     type Err = String;
     fn from_str(s: &str) -> std::result::Result<SystemsListFlakeRef, String> {
-        // Systems lists recognized by `github:nix-system/*`
-        let url = if let Some(nix_system_flake) = NIX_SYSTEMS.get(s) {
-            nix_system_flake.clone()
-        } else {
-            FlakeUrl(s.to_string())
-        };
-        Ok(SystemsListFlakeRef(url))
+        // Systems lists recognized by `github:nix-system/*`. If the builtin
+        // table itself failed to parse, surface that `ConfigError` instead
+        // of silently guessing whether `s` was meant as a shorthand name.
+        let table = nix_systems().map_err(|err| err.to_string())?;
+        let url = table.get(s).cloned().unwrap_or_else(|| FlakeUrl(s.to_string()));
+        let flake_ref = FlakeRef::from_str(&url.0).map_err(|err| err.to_string())?;
+        Ok(SystemsListFlakeRef(flake_ref))
+    }
+}
+
+impl<'de> Deserialize<'de> for SystemsListFlakeRef {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        SystemsListFlakeRef::from_str(&s).map_err(D::Error::custom)
     }
 }
 
@@ -46,22 +72,43 @@ impl SystemsList {
     }
 
     async fn from_remote_flake(cmd: &NixCmd, url: &SystemsListFlakeRef) -> Result<Self> {
-        let systems = nix_import_flake::<Vec<System>>(cmd, &url.0).await?;
+        let systems = nix_import_flake::<Vec<System>>(cmd, &url.0.to_flake_url()).await?;
         Ok(SystemsList(systems))
     }
 
     /// Handle known repos of <https://github.com/nix-systems> thereby avoiding
     /// network calls.
     fn from_known_flake(url: &SystemsListFlakeRef) -> Option<Self> {
-        let system = NIX_SYSTEMS
+        let flake_url = url.0.to_flake_url();
+        let table = nix_systems().ok()?;
+        let system = table
             .iter()
-            .find_map(|(v, u)| if u == &url.0 { Some(v) } else { None })?;
+            .find_map(|(v, u)| if u == &flake_url { Some(v) } else { None })?;
         Some(SystemsList(vec![system.clone().into()]))
     }
+
+    /// Filter this list down to the systems for which `condition` (if any)
+    /// evaluates to `true` for the given `subflake` name.
+    ///
+    /// Absent a condition, the list is returned unchanged.
+    pub fn filtered(&self, condition: Option<&CelCondition>, subflake: &str) -> Result<Self> {
+        let Some(condition) = condition else {
+            return Ok(SystemsList(self.0.clone()));
+        };
+        let systems = self
+            .0
+            .iter()
+            .map(|system| Ok((system.clone(), condition.eval(system, subflake)?)))
+            .collect::<Result<Vec<(System, bool)>>>()?
+            .into_iter()
+            .filter_map(|(system, keep)| keep.then_some(system))
+            .collect();
+        Ok(SystemsList(systems))
+    }
 }
 
 /// Evaluate `import <flake-url>` and return the result JSON parsed.
-pub async fn nix_import_flake<T>(cmd: &NixCmd, url: &FlakeUrl) -> Result<T, NixCmdError>
+pub async fn nix_import_flake<T>(cmd: &NixCmd, url: &FlakeUrl) -> Result<T>
 where
     T: Default + serde::de::DeserializeOwned,
 {
@@ -71,13 +118,14 @@ where
     Ok(v)
 }
 
-async fn nix_eval_impure_expr<T>(cmd: &NixCmd, expr: String) -> Result<T, NixCmdError>
+async fn nix_eval_impure_expr<T>(cmd: &NixCmd, expr: String) -> Result<T>
 where
     T: Default + serde::de::DeserializeOwned,
 {
     let v = cmd
         .run_with_args_expecting_json::<T>(&["eval", "--impure", "--json", "--expr", &expr])
-        .await?;
+        .await
+        .map_err(ConfigError::Nix)?;
     Ok(v)
 }
 
@@ -89,7 +137,7 @@ mod tests {
     async fn test_empty_systems_list() {
         let systems = SystemsList::from_flake(
             &NixCmd::default(),
-            &SystemsListFlakeRef(FlakeUrl("github:nix-systems/empty".to_string())),
+            &SystemsListFlakeRef(FlakeRef::from_str("github:nix-systems/empty").unwrap()),
         )
         .await
         .unwrap();
@@ -133,8 +181,8 @@ mod tests {
 
     async fn assert_systems_list(url: &str, expected: Vec<System>) {
         let cmd = NixCmd::default();
-        let flake_url = FlakeUrl::from_str(url).unwrap();
-        let systems = SystemsList::from_flake(&cmd, &SystemsListFlakeRef(flake_url))
+        let flake_ref = FlakeRef::from_str(url).unwrap();
+        let systems = SystemsList::from_flake(&cmd, &SystemsListFlakeRef(flake_ref))
             .await
             .unwrap();
         assert_eq!(systems.0, expected);